@@ -1,57 +1,226 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use std::env;
-use std::io;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
 use std::process;
 use grep_starter_rust::regex::RegexPattern;
 
-fn parse_pattern(pattern: &str) -> Result<RegexPattern> {
-    let mut stream = pattern.chars();
-    let res = match stream.next() {
-        Some('\\') => {
-            match stream.next() {
-                Some('d') => Ok(RegexPattern::Digit),
-                Some(chr) => Ok(RegexPattern::Char(chr)),
-                None => bail!("trailing backlash (\\)"),
+struct Options {
+    pattern: String,
+    recursive: bool,
+    count_only: bool,
+    invert: bool,
+    line_numbers: bool,
+    verbose: bool,
+    paths: Vec<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    args.next(); // argv[0]
+
+    if args.next().as_deref() != Some("-E") {
+        bail!("Expected first argument to be '-E'");
+    }
+    let pattern = args.next().context("Expected a pattern after '-E'")?;
+
+    let mut recursive = false;
+    let mut count_only = false;
+    let mut invert = false;
+    let mut line_numbers = false;
+    let mut verbose = false;
+    let mut paths = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "-r" | "--recursive" => recursive = true,
+            "-c" => count_only = true,
+            "-v" => invert = true,
+            "-n" => line_numbers = true,
+            "-x" => verbose = true,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    Ok(Options { pattern, recursive, count_only, invert, line_numbers, verbose, paths })
+}
+
+// Expands `path` into the plain files it refers to, walking directories when
+// `recursive` is set and erroring on a bare directory otherwise.
+fn collect_files(path: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        if !recursive {
+            bail!("{}: Is a directory", path.display());
+        }
+        let entries = fs::read_dir(path)
+            .with_context(|| format!("reading directory {}", path.display()))?;
+        for entry in entries {
+            collect_files(&entry?.path(), recursive, files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+// Returns the (1-based line number, line contents) of every line in `reader`
+// that matches `pattern`, with `invert` flipping the sense of "matches".
+fn matching_lines(reader: impl BufRead, pattern: &RegexPattern, invert: bool) -> Result<Vec<(usize, String)>> {
+    reader.lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let outcome = line.map_err(anyhow::Error::from).and_then(|line| {
+                let matched = pattern.is_contained_in(&line)?;
+                Ok((matched, index + 1, line))
+            });
+            match outcome {
+                Ok((matched, number, line)) => (matched != invert).then_some(Ok((number, line))),
+                Err(error) => Some(Err(error)),
             }
+        })
+        .collect()
+}
+
+fn print_matches(name: Option<&str>, matches: &[(usize, String)], count_only: bool, line_numbers: bool) {
+    if count_only {
+        match name {
+            Some(name) => println!("{name}:{}", matches.len()),
+            None => println!("{}", matches.len()),
         }
-        Some(chr) => {
-            Ok(RegexPattern::Char(chr))
+        return;
+    }
+
+    for (number, line) in matches {
+        let mut prefix = String::new();
+        if let Some(name) = name {
+            prefix.push_str(name);
+            prefix.push(':');
         }
-        None => {
-            Ok(RegexPattern::Empty)
+        if line_numbers {
+            prefix.push_str(&number.to_string());
+            prefix.push(':');
         }
-    };
+        println!("{prefix}{line}");
+    }
+}
 
-    if stream.next().is_none() {
-        res
+// Runs the search described by `options` and reports whether anything matched.
+fn run(options: Options) -> Result<bool> {
+    let pattern = if options.verbose && !options.pattern.starts_with("(?x)") {
+        RegexPattern::parse(&format!("(?x){}", options.pattern))?
     } else {
-        bail!("Unhandled pattern: {pattern}")
+        RegexPattern::parse(&options.pattern)?
+    };
+
+    if options.paths.is_empty() {
+        let matches = matching_lines(io::stdin().lock(), &pattern, options.invert)?;
+        let any_match = !matches.is_empty();
+        print_matches(None, &matches, options.count_only, options.line_numbers);
+        return Ok(any_match)
     }
-}
 
-// Usage: echo <input_text> | your_grep.sh -E <pattern>
-fn main() {
-    if env::args().nth(1).unwrap() != "-E" {
-        println!("Expected first argument to be '-E'");
-        process::exit(1);
+    let mut files = vec![];
+    for path in &options.paths {
+        collect_files(path, options.recursive, &mut files)?;
     }
 
-    let pattern = env::args().nth(2).unwrap();
-    let mut input_line = String::new();
+    // Only prefix lines with their filename when there's more than one file to
+    // tell apart or the search is recursive, matching grep's own behavior for a
+    // single plain file argument.
+    let show_names = files.len() > 1 || options.recursive;
 
-    io::stdin().read_line(&mut input_line).unwrap();
-    match parse_pattern(&pattern) {
-        Ok(pat) => {
-            if pat.is_contained_in(&input_line) {
-                process::exit(0)
-            } else {
-                process::exit(1)
-            }
+    let mut any_match = false;
+    for file in files {
+        let reader = io::BufReader::new(
+            fs::File::open(&file).with_context(|| format!("opening {}", file.display()))?
+        );
+        let matches = matching_lines(reader, &pattern, options.invert)?;
+        any_match |= !matches.is_empty();
+        let name = show_names.then(|| file.display().to_string());
+        print_matches(name.as_deref(), &matches, options.count_only, options.line_numbers);
+    }
+
+    Ok(any_match)
+}
+
+// Usage: your_grep.sh -E <pattern> [-r|--recursive] [-n] [-c] [-v] [-x] [path ...]
+// With no paths, searches every line read from stdin.
+fn main() {
+    let options = match parse_args(env::args()) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}");
+            process::exit(1)
         }
+    };
+
+    match run(options) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
         Err(error) => {
             eprintln!("{error}");
             process::exit(1)
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
 
+    fn args(parts: &[&str]) -> impl Iterator<Item = String> {
+        parts.iter().map(|part| part.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn parse_args_reads_flags_and_paths() {
+        let options = parse_args(args(&["grep", "-E", "ab+", "-n", "-r", "src", "tests"])).unwrap();
+        assert_eq!(options.pattern, "ab+");
+        assert!(options.line_numbers);
+        assert!(options.recursive);
+        assert!(!options.count_only);
+        assert_eq!(options.paths, vec![PathBuf::from("src"), PathBuf::from("tests")]);
+    }
+
+    #[test]
+    fn parse_args_requires_dash_e_first() {
+        assert!(parse_args(args(&["grep", "ab+"])).is_err());
+    }
+
+    #[test]
+    fn matching_lines_respects_invert() {
+        let pattern = RegexPattern::parse("ab").unwrap();
+        let reader = Cursor::new(b"abc\ndef\nxab\n".to_vec());
+        let matches = matching_lines(reader, &pattern, false).unwrap();
+        assert_eq!(matches, vec![(1, "abc".to_string()), (3, "xab".to_string())]);
+
+        let reader = Cursor::new(b"abc\ndef\nxab\n".to_vec());
+        let inverted = matching_lines(reader, &pattern, true).unwrap();
+        assert_eq!(inverted, vec![(2, "def".to_string())]);
+    }
+
+    #[test]
+    fn collect_files_walks_directories_recursively() {
+        let dir = env::temp_dir().join(format!("grep_starter_rust_test_{}", process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.txt"), "").unwrap();
+        fs::write(dir.join("nested/b.txt"), "").unwrap();
+
+        let mut files = vec![];
+        collect_files(&dir, true, &mut files).unwrap();
+        files.sort();
+        let mut expected = vec![dir.join("a.txt"), dir.join("nested/b.txt")];
+        expected.sort();
+        assert_eq!(files, expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn collect_files_rejects_directory_without_recursive() {
+        let dir = env::temp_dir();
+        let mut files = vec![];
+        assert!(collect_files(&dir, false, &mut files).is_err());
+    }
 }
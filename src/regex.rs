@@ -1,8 +1,13 @@
+use std::iter::Peekable;
 use std::str::Chars;
 use anyhow::{bail, Result};
 use itertools::Itertools;
 
-#[derive(Debug)]
+// One slot per capture group, holding the byte span (start, end) into the original
+// haystack that the group last matched, or `None` if it hasn't matched (yet).
+type Captures = Vec<Option<(usize, usize)>>;
+
+#[derive(Debug, Clone)]
 pub enum RegexClass {
     Char(char),
     AlphaNum,
@@ -13,19 +18,49 @@ pub enum RegexClass {
     Optional(Box<RegexClass>),
     Sequence(Vec<RegexClass>),
     Alternation(Vec<RegexClass>),
+    Group(usize, Box<RegexClass>),
+    Backreference(usize),
+    // `true` for `\b` (boundary), `false` for `\B` (non-boundary).
+    WordBoundary(bool),
     OneOrMorePlaceholder,
     OptionalPlaceholder,
     Empty,
+    // Internal: closes capture group `.0`, whose match started at byte `.1` of the
+    // haystack. Never produced by the parser; `Group` injects it as a continuation.
+    GroupEnd(usize, usize),
+    // Internal: tries another repetition of `.0`, unless the previous repetition
+    // ended where it started (`.1`), in which case repeating again would never
+    // make progress and we stop instead. Never produced by the parser;
+    // `OneOrMore` injects it as a continuation.
+    OneOrMoreContinue(Box<RegexClass>, usize),
 }
 
-macro_rules! simple_match {
-    ($expression:expr) => {
-        if $expression {
-            (true, 1)
-        } else {
-            (false, 0)
-        }
-    };
+fn is_word_char(c: char) -> bool {
+    matches!(c, '0'..='9' | 'a'..='z' | 'A'..='Z' | '_')
+}
+
+// Matches a single character against `pred`, and on success hands the rest of the
+// haystack to `rest` so the enclosing sequence can keep going (or backtrack).
+fn match_single_char(
+    base: &str,
+    pos: usize,
+    rest: &[RegexClass],
+    captures: &mut Captures,
+    pred: impl Fn(char) -> bool,
+) -> Result<Option<usize>> {
+    match base[pos..].chars().next() {
+        Some(c) if pred(c) => match_rest(rest, base, pos + c.len_utf8(), captures),
+        _ => Ok(None),
+    }
+}
+
+// Matches a flat list of nodes in order, each one handing its own tail of the
+// list to the next as its continuation. An empty list always matches (zero-width).
+fn match_rest(rest: &[RegexClass], base: &str, pos: usize, captures: &mut Captures) -> Result<Option<usize>> {
+    match rest.split_first() {
+        None => Ok(Some(pos)),
+        Some((first, tail)) => first.matches(base, pos, tail, captures),
+    }
 }
 
 impl RegexClass {
@@ -48,90 +83,128 @@ impl RegexClass {
             RegexClass::Digit |
             RegexClass::Char(..) |
             RegexClass::CharGroup(..) => 1,
+            RegexClass::Group(_, pat) => pat.min_size()?,
+            // The referenced group's length isn't known until match time.
+            RegexClass::Backreference(..) => 0,
+            // Zero-width assertion.
+            RegexClass::WordBoundary(..) => 0,
             RegexClass::Empty |
             RegexClass::OptionalPlaceholder |
-            RegexClass::OneOrMorePlaceholder => bail!("placeholder values don't have a size")
+            RegexClass::OneOrMorePlaceholder |
+            RegexClass::GroupEnd(..) |
+            RegexClass::OneOrMoreContinue(..) => bail!("placeholder values don't have a size")
         })
     }
 
-    fn matches(&self, haystack: &str) -> Result<(bool, usize)>{
-        Ok(match self {
+    // Tries to match `self` at `base[pos..]`, then hands the resulting position to
+    // `rest` (the rest of the enclosing `Sequence`) as a continuation. Returns the
+    // absolute position reached after `self` *and* `rest` match, so a greedy node
+    // can back off and let an earlier choice retry when `rest` fails. `captures`
+    // holds the byte spans recorded so far by `Group`, indexed by group number - 1.
+    fn matches(&self, base: &str, pos: usize, rest: &[RegexClass], captures: &mut Captures) -> Result<Option<usize>> {
+        match self {
             RegexClass::Char(pat) => {
-                simple_match!(haystack.chars().next().is_some_and(|c| c == *pat))
+                let pat = *pat;
+                match_single_char(base, pos, rest, captures, |c| c == pat)
             }
             RegexClass::Digit => {
-                simple_match!(
-                    haystack.chars().next().is_some_and(|c| match c {
-                        '0'..='9' => true,
-                        _ => false
-                    })
-                )
+                match_single_char(base, pos, rest, captures, |c| matches!(c, '0'..='9'))
             }
             RegexClass::AlphaNum => {
-                simple_match!(
-                    haystack.chars().next().is_some_and(|c| match c {
-                        '0'..='9' | 'a'..='z' | 'A'..='Z' | '_' => true,
-                        _ => false
-                    })
-                )
+                match_single_char(base, pos, rest, captures, is_word_char)
             }
             RegexClass::Wildcard => {
-                simple_match!(haystack.chars().next().is_some_and(|c| c != '\n'))
+                match_single_char(base, pos, rest, captures, |c| c != '\n')
             }
             RegexClass::CharGroup((set, polarity)) => {
-                simple_match!(
-                    haystack.chars().next().is_some_and(|c| if set.contains(&c) {
-                        *polarity
-                    } else {
-                        !*polarity
-                    })
-                )
+                match_single_char(base, pos, rest, captures, |c| set.contains(&c) == *polarity)
             }
             RegexClass::OneOrMore(pat) => {
-                let mut consumed = 0usize;
-
-                loop {
-                    let (matches, length) = pat.matches(&haystack[consumed..])?;
-                    if !matches {
-                        break
-                    } else {
-                        consumed += length
-                    }
+                // The first repetition is mandatory: try it, then let
+                // `OneOrMoreContinue` decide (via ordinary backtracking) whether to
+                // repeat again or hand off to `rest`.
+                let mut continuation = vec![RegexClass::OneOrMoreContinue(pat.clone(), pos)];
+                continuation.extend(rest.iter().cloned());
+                pat.matches(base, pos, &continuation, captures)
+            }
+            RegexClass::OneOrMoreContinue(pat, started_at) => {
+                if pos == *started_at {
+                    // The previous repetition consumed nothing, so repeating `pat`
+                    // again would loop forever without making progress - stop here.
+                    return match_rest(rest, base, pos, captures)
                 }
-
-                (consumed > 0, consumed)
+                // Try the longest match first: one more repetition, then either keep
+                // repeating or fall through to `rest`; back off to stopping here if
+                // that doesn't pan out.
+                let mut continuation = vec![RegexClass::OneOrMoreContinue(pat.clone(), pos)];
+                continuation.extend(rest.iter().cloned());
+                if let Some(end) = pat.matches(base, pos, &continuation, captures)? {
+                    return Ok(Some(end))
+                }
+                match_rest(rest, base, pos, captures)
             }
             RegexClass::Optional(pat) => {
-                (true, pat.matches(haystack)?.1)
+                // Present branch first (greedy), then the absent branch.
+                if let Some(end) = pat.matches(base, pos, rest, captures)? {
+                    Ok(Some(end))
+                } else {
+                    match_rest(rest, base, pos, captures)
+                }
             }
             RegexClass::Sequence(seq) => {
-                let mut consumed = 0usize;
-
+                let mut combined = seq.clone();
+                combined.extend(rest.iter().cloned());
+                match_rest(&combined, base, pos, captures)
+            }
+            RegexClass::Alternation(seq) => {
                 for pat in seq {
-                    let (matches, length) = pat.matches(&haystack[consumed..])?;
-                    if !matches {
-                        return Ok((false, 0))
-                    } else {
-                        consumed += length;
+                    if let Some(end) = pat.matches(base, pos, rest, captures)? {
+                        return Ok(Some(end))
                     }
                 }
-
-                (true, consumed)
+                Ok(None)
             }
-            RegexClass::Alternation(seq) => {
-                for pat in seq {
-                    let (matches, length) = pat.matches(&haystack)?;
-                    if matches {
-                        return Ok((true, length))
+            RegexClass::Group(index, pat) => {
+                let mut continuation = vec![RegexClass::GroupEnd(*index, pos)];
+                continuation.extend(rest.iter().cloned());
+                pat.matches(base, pos, &continuation, captures)
+            }
+            RegexClass::GroupEnd(index, start) => {
+                let slot = *index - 1;
+                let previous = captures[slot];
+                captures[slot] = Some((*start, pos));
+                let result = match_rest(rest, base, pos, captures)?;
+                if result.is_none() {
+                    captures[slot] = previous;
+                }
+                Ok(result)
+            }
+            RegexClass::Backreference(index) => {
+                match captures.get(*index - 1).copied().flatten() {
+                    None => Ok(None),
+                    Some((start, end)) => {
+                        let captured = &base[start..end];
+                        if base[pos..].starts_with(captured) {
+                            match_rest(rest, base, pos + captured.len(), captures)
+                        } else {
+                            Ok(None)
+                        }
                     }
                 }
-
-                (false, 0)
+            }
+            RegexClass::WordBoundary(expect_boundary) => {
+                let before_is_word = base[..pos].chars().next_back().is_some_and(is_word_char);
+                let after_is_word = base[pos..].chars().next().is_some_and(is_word_char);
+                if (before_is_word != after_is_word) == *expect_boundary {
+                    match_rest(rest, base, pos, captures)
+                } else {
+                    Ok(None)
+                }
             }
             RegexClass::Empty |
             RegexClass::OneOrMorePlaceholder |
             RegexClass::OptionalPlaceholder => bail!("placeholder class can't match anything")
-        })
+        }
     }
 }
 
@@ -147,20 +220,47 @@ pub struct RegexPattern {
     at_start: bool,
     until_end: bool,
     sequence: RegexClass,
+    group_count: usize,
+}
+
+// In verbose (`x`) mode, skips runs of unescaped whitespace and `#`-to-end-of-line
+// comments ahead of the next fragment. A no-op outside of verbose mode.
+fn skip_insignificant(chars: &mut Peekable<Chars>, verbose: bool) {
+    if !verbose {
+        return
+    }
+    loop {
+        match chars.peek() {
+            Some(c) if c.is_whitespace() => { chars.next(); }
+            Some('#') => {
+                while !matches!(chars.next(), None | Some('\n')) {}
+            }
+            _ => break,
+        }
+    }
 }
 
-fn parse_fragment(chars: &mut Chars) -> Result<RegexClass> {
+fn parse_fragment(chars: &mut Peekable<Chars>, next_group: &mut usize, verbose: bool) -> Result<RegexClass> {
+    skip_insignificant(chars, verbose);
     if let Some(chr) = chars.next() {
         match chr {
             '\\' => {
                 match chars.next() {
                     Some('d') => Ok(RegexClass::Digit),
                     Some('w') => Ok(RegexClass::AlphaNum),
+                    Some('b') => Ok(RegexClass::WordBoundary(true)),
+                    Some('B') => Ok(RegexClass::WordBoundary(false)),
+                    Some('0') => bail!("group numbers are 1-based, \\0 is not a valid backreference"),
+                    Some(chr) if chr.is_ascii_digit() => {
+                        Ok(RegexClass::Backreference(chr.to_digit(10).unwrap() as usize))
+                    }
+                    // This is also how an escaped space (`\ `) stays a literal space in verbose mode.
                     Some(chr) => Ok(RegexClass::Char(chr)),
                     None => bail!("trailing backlash (\\)"),
                 }
             }
             '[' => {
+                // Whitespace inside a character class is always literal, verbose or not.
                 let mut set = vec![];
                 let mut polarity = true;
                 while let Some(chr) = chars.next() {
@@ -173,9 +273,12 @@ fn parse_fragment(chars: &mut Chars) -> Result<RegexClass> {
                 bail!("brackets ([ ]) not balanced")
             }
             '(' => {
+                let index = *next_group;
+                *next_group += 1;
+
                 let mut alternatives = vec![];
                 loop {
-                    if let Ok((seq, stopped_at)) = parse_sequence(chars, "|)") {
+                    if let Ok((seq, stopped_at)) = parse_sequence(chars, "|)", next_group, verbose) {
                         alternatives.push(seq);
                         if stopped_at == Some(')') {
                             break;
@@ -184,7 +287,7 @@ fn parse_fragment(chars: &mut Chars) -> Result<RegexClass> {
                         bail!("parentheses not balanced")
                     }
                 }
-                Ok(RegexClass::Alternation(alternatives))
+                Ok(RegexClass::Group(index, Box::new(RegexClass::Alternation(alternatives))))
             }
             '+' => Ok(RegexClass::OneOrMorePlaceholder),
             '?' => Ok(RegexClass::OptionalPlaceholder),
@@ -196,10 +299,10 @@ fn parse_fragment(chars: &mut Chars) -> Result<RegexClass> {
     }
 }
 
-fn parse_sequence(chars: &mut Chars, stop: &str) -> Result<(RegexClass, Option<char>)> {
+fn parse_sequence(chars: &mut Peekable<Chars>, stop: &str, next_group: &mut usize, verbose: bool) -> Result<(RegexClass, Option<char>)> {
     let mut seq = vec![];
     loop {
-        let next = parse_fragment(chars)?;
+        let next = parse_fragment(chars, next_group, verbose)?;
         match next {
             RegexClass::Empty => {
                 if stop.len() == 0 {
@@ -236,6 +339,26 @@ fn parse_sequence(chars: &mut Chars, stop: &str) -> Result<(RegexClass, Option<c
 
 impl RegexPattern {
     pub fn parse(pattern: &str) -> Result<Self> {
+        let (verbose, pattern) = match pattern.strip_prefix("(?x)") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        // Trims whitespace off both ends, but keeps an escaped space (`\ `) at the
+        // very end, since that one is a literal. Any other whitespace kept after a
+        // trailing `\` is skipped by the verbose parser anyway.
+        fn trim_verbose(pattern: &str) -> &str {
+            let start = pattern.trim_start();
+            let end = start.trim_end();
+            match start[end.len()..].chars().next() {
+                Some(c) if end.ends_with('\\') => &start[..end.len() + c.len_utf8()],
+                _ => end,
+            }
+        }
+
+        // In verbose mode, whitespace around the anchors is just as insignificant
+        // as whitespace anywhere else, so trim it before looking for `^`/`$`.
+        let pattern = if verbose { trim_verbose(pattern) } else { pattern };
+
         let mut at_start = false;
         let mut until_end = false;
 
@@ -247,12 +370,16 @@ impl RegexPattern {
             until_end = true;
             &pattern[..pattern.len()-1]
         } else { pattern };
+        let pattern = if verbose { trim_verbose(pattern) } else { pattern };
 
-        let (sequence, _) = parse_sequence(&mut pattern.chars(), "")?;
+        let mut next_group = 1usize;
+        let mut chars = pattern.chars().peekable();
+        let (sequence, _) = parse_sequence(&mut chars, "", &mut next_group, verbose)?;
         Ok(RegexPattern {
             at_start,
             until_end,
-            sequence
+            sequence,
+            group_count: next_group - 1,
         })
     }
 
@@ -264,18 +391,17 @@ impl RegexPattern {
         }
 
         if self.at_start {
-            let (matches, length) = self.sequence.matches(haystack)?;
-            if self.until_end {
-                return Ok(matches && (length == hlen))
-            } else {
-                return Ok(matches)
-            }
+            let mut captures = vec![None; self.group_count];
+            return Ok(match self.sequence.matches(haystack, 0, &[], &mut captures)? {
+                Some(end) => !self.until_end || end == hlen,
+                None => false,
+            })
         }
 
         for offset in 0..=(hlen - min_size) {
-            let (matches, length) = self.sequence.matches(&haystack[(offset)..])?;
-            if matches {
-                if self.until_end && length != (hlen - offset) {
+            let mut captures = vec![None; self.group_count];
+            if let Some(end) = self.sequence.matches(haystack, offset, &[], &mut captures)? {
+                if self.until_end && end != hlen {
                     continue
                 }
                 return Ok(true)
@@ -283,4 +409,184 @@ impl RegexPattern {
         }
         Ok(false)
     }
+
+    /// Compiles a shell-style glob (`*`, `?`, `[abc]`, `[!abc]`) into the same
+    /// `RegexClass` tree the regex parser builds, implicitly anchored at both ends.
+    pub fn parse_glob(glob: &str) -> Result<Self> {
+        let mut chars = glob.chars();
+        let mut seq = vec![];
+
+        while let Some(chr) = chars.next() {
+            match chr {
+                '*' => seq.push(RegexClass::Optional(Box::new(
+                    RegexClass::OneOrMore(Box::new(non_slash_wildcard()))
+                ))),
+                '?' => seq.push(non_slash_wildcard()),
+                '[' => {
+                    let mut set = vec![];
+                    let mut polarity = true;
+                    let mut closed = false;
+                    while let Some(chr) = chars.next() {
+                        match chr {
+                            ']' => { closed = true; break }
+                            '!' if set.is_empty() => polarity = false,
+                            _ => if !set.contains(&chr) { set.push(chr) }
+                        }
+                    }
+                    if !closed {
+                        bail!("brackets ([ ]) not balanced")
+                    }
+                    seq.push(RegexClass::CharGroup((set, polarity)));
+                }
+                chr => seq.push(RegexClass::Char(chr)),
+            }
+        }
+
+        Ok(RegexPattern {
+            at_start: true,
+            until_end: true,
+            sequence: RegexClass::Sequence(seq),
+            group_count: 0,
+        })
+    }
+}
+
+// A single character that isn't a path separator, used to keep `*`/`?` from
+// crossing `/` the way shell globs do.
+fn non_slash_wildcard() -> RegexClass {
+    RegexClass::CharGroup((vec!['/'], false))
+}
+
+/// Compiles several patterns at once, the way `regex::RegexSet` does. `is_match`
+/// short-circuits on the first hit; `matches` always walks the whole list so it
+/// can report the index of every pattern that matched.
+pub struct RegexSet {
+    patterns: Vec<RegexPattern>,
+}
+
+impl RegexSet {
+    pub fn parse(patterns: &[&str]) -> Result<Self> {
+        let patterns = patterns.iter()
+            .map(|pattern| RegexPattern::parse(pattern))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RegexSet { patterns })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> Result<bool> {
+        for pattern in &self.patterns {
+            if pattern.is_contained_in(haystack)? {
+                return Ok(true)
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn matches(&self, haystack: &str) -> Result<Vec<usize>> {
+        self.patterns.iter()
+            .enumerate()
+            .filter_map(|(index, pattern)| match pattern.is_contained_in(haystack) {
+                Ok(true) => Some(Ok(index)),
+                Ok(false) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `+` must keep backtracking into alternation choices made during earlier
+    // repetitions, not just greedily commit to whichever branch matched first.
+    #[test]
+    fn one_or_more_backtracks_through_alternation() {
+        let pattern = RegexPattern::parse("^(ab|a)+b$").unwrap();
+        assert!(pattern.is_contained_in("ab").unwrap());
+        assert!(pattern.is_contained_in("aab").unwrap());
+        assert!(pattern.is_contained_in("abab").unwrap());
+    }
+
+    // A repetition that consumes nothing (e.g. a word boundary) must stop
+    // instead of looping forever.
+    #[test]
+    fn one_or_more_stops_on_zero_width_repetition() {
+        let pattern = RegexPattern::parse(r"^(\b)+a$").unwrap();
+        assert!(pattern.is_contained_in("a").unwrap());
+    }
+
+    // Each repetition of a group under `+` must capture its own span, so a
+    // trailing backreference sees the digit from the *last* repetition only.
+    #[test]
+    fn backreference_sees_last_repetition_of_captured_group() {
+        let pattern = RegexPattern::parse(r"^(\d)+\1$").unwrap();
+        assert!(!pattern.is_contained_in("1235").unwrap());
+        assert!(pattern.is_contained_in("1233").unwrap());
+    }
+
+    #[test]
+    fn backreference_requires_exact_match() {
+        let pattern = RegexPattern::parse(r"^(cat|dog) and \1$").unwrap();
+        assert!(pattern.is_contained_in("cat and cat").unwrap());
+        assert!(!pattern.is_contained_in("cat and dog").unwrap());
+    }
+
+    // Group numbering is 1-based, so `\0` is never a valid backreference and
+    // must be rejected at parse time rather than panicking at match time.
+    #[test]
+    fn backreference_to_group_zero_is_rejected() {
+        assert!(RegexPattern::parse(r"a\0b").is_err());
+    }
+
+    #[test]
+    fn verbose_mode_ignores_whitespace_and_comments() {
+        let pattern = RegexPattern::parse("(?x) a b c # trailing comment").unwrap();
+        assert!(pattern.is_contained_in("xabcx").unwrap());
+    }
+
+    #[test]
+    fn verbose_mode_ignores_whitespace_around_anchors() {
+        let pattern = RegexPattern::parse("(?x) ^abc$ ").unwrap();
+        assert!(pattern.is_contained_in("abc").unwrap());
+        assert!(!pattern.is_contained_in("xabc").unwrap());
+    }
+
+    #[test]
+    fn regex_set_is_match_short_circuits_on_first_hit() {
+        let set = RegexSet::parse(&["cat", "dog", "bird"]).unwrap();
+        assert!(set.is_match("the dog barked").unwrap());
+        assert!(!set.is_match("the fish swam").unwrap());
+    }
+
+    #[test]
+    fn regex_set_matches_reports_every_matching_index() {
+        let set = RegexSet::parse(&["cat", "dog", "bird"]).unwrap();
+        assert_eq!(set.matches("a cat chased a bird").unwrap(), vec![0, 2]);
+        assert_eq!(set.matches("nothing here").unwrap(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn glob_star_matches_within_a_path_segment_only() {
+        let pattern = RegexPattern::parse_glob("*.rs").unwrap();
+        assert!(pattern.is_contained_in("main.rs").unwrap());
+        assert!(!pattern.is_contained_in("src/main.rs").unwrap());
+    }
+
+    #[test]
+    fn glob_question_mark_matches_exactly_one_char() {
+        let pattern = RegexPattern::parse_glob("?.rs").unwrap();
+        assert!(pattern.is_contained_in("a.rs").unwrap());
+        assert!(!pattern.is_contained_in("ab.rs").unwrap());
+    }
+
+    #[test]
+    fn glob_bracket_set_and_negated_set() {
+        let pattern = RegexPattern::parse_glob("[abc].txt").unwrap();
+        assert!(pattern.is_contained_in("a.txt").unwrap());
+        assert!(!pattern.is_contained_in("d.txt").unwrap());
+
+        let pattern = RegexPattern::parse_glob("[!abc].txt").unwrap();
+        assert!(pattern.is_contained_in("d.txt").unwrap());
+        assert!(!pattern.is_contained_in("a.txt").unwrap());
+    }
 }
\ No newline at end of file